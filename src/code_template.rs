@@ -1,6 +1,10 @@
+mod common;
+mod csrf;
+mod schema;
+
 use actix_cors::Cors;
 
-use actix_web::{http::header, web, App, HttpResponse, HttpServer, Responder};
+use actix_web::{http::header, http::Method, web, App, HttpResponse, HttpServer};
 
 use serde::{Deserialize, Serialize};
 
@@ -8,186 +12,200 @@ use reqwest::Client as HttpClient;
 
 use async_trait::async_trait;
 
-use std::collections::HashMap;
-use std::fs;
-use std::io::Write;
-use std::sync::Mutex;
+use common::{next_counter_seed, next_id, ApiError, AuthUser, DbPool, JwtSecretSource, UserStore};
+
+use diesel::prelude::*;
+
+use sqids::Sqids;
 
-#[derive(Serialize, Debug, Deserialize, Clone)]
+use std::sync::atomic::AtomicU64;
+
+#[derive(Serialize, Debug, Deserialize, Clone, Queryable, Insertable, AsChangeset)]
+#[diesel(table_name = schema::tasks)]
 struct Task {
-    id: u64,
+    id: String,
     name: String,
     completed: bool,
 }
 
-#[derive(Serialize, Deserialize)]
-struct User {
-    id: u64,
-    username: String,
-    password: String,
-}
-
-#[derive(Serialize, Deserialize)]
-struct Database {
-    tasks: HashMap<u64, Task>,
-    users: HashMap<u64, User>,
+#[derive(Deserialize)]
+struct ListParams {
+    limit: Option<usize>,
+    offset: Option<usize>,
+    sort: Option<String>,
+    order: Option<String>,
+    completed: Option<bool>,
 }
 
-impl Database {
-    fn new() -> Self {
-        Self {
-            tasks: HashMap::new(),
-            users: HashMap::new(),
-        }
-    }
+// CRUD DATA
 
-    // CRUD DATA
-
-    fn insert(&mut self, task: Task) {
-        self.tasks.insert(task.id, task);
-    }
+fn insert_task(conn: &mut SqliteConnection, task: &Task) -> Result<(), ApiError> {
+    diesel::replace_into(schema::tasks::table)
+        .values(task)
+        .execute(conn)?;
+    Ok(())
+}
 
-    fn get(&self, id: &u64) -> Option<&Task> {
-        self.tasks.get(id)
-    }
+fn get_task(conn: &mut SqliteConnection, task_id: &str) -> Result<Option<Task>, ApiError> {
+    let result = schema::tasks::table
+        .find(task_id)
+        .first::<Task>(conn)
+        .optional()?;
+    Ok(result)
+}
 
-    fn get_all(&self) -> Vec<&Task> {
-        self.tasks.values().collect()
-    }
+fn get_all_tasks(conn: &mut SqliteConnection) -> Result<Vec<Task>, ApiError> {
+    Ok(schema::tasks::table.load::<Task>(conn)?)
+}
 
-    fn delete(&mut self, id: &u64) {
-        self.tasks.remove(id);
-    }
+fn delete_task_by_id(conn: &mut SqliteConnection, task_id: &str) -> Result<(), ApiError> {
+    diesel::delete(schema::tasks::table.find(task_id)).execute(conn)?;
+    Ok(())
+}
 
-    fn update(&mut self, task: Task) {
-        self.tasks.insert(task.id, task);
-    }
+fn update_task_row(conn: &mut SqliteConnection, task: &Task) -> Result<(), ApiError> {
+    get_task(conn, &task.id)?.ok_or(ApiError::NotFound)?;
+    diesel::update(schema::tasks::table.find(&task.id))
+        .set(task)
+        .execute(conn)?;
+    Ok(())
+}
 
-    // USER CRUD
+struct AppState {
+    pool: DbPool,
+    jwt_secret: String,
+    sqids: Sqids,
+    tasks_counter: AtomicU64,
+    users_counter: AtomicU64,
+}
 
-    fn insert_user(&mut self, user: User) {
-        self.users.insert(user.id, user);
+impl JwtSecretSource for AppState {
+    fn jwt_secret(&self) -> &str {
+        &self.jwt_secret
     }
+}
 
-    fn get_user_by_name(&self, username: &str) -> Option<&User> {
-        self.users.values().find(|u| u.username == username)
+impl UserStore for AppState {
+    fn pool(&self) -> &DbPool {
+        &self.pool
     }
 
-    // SAVE DATABASE
-
-    fn save_to_file(&self) -> std::io::Result<()> {
-        let data = serde_json::to_string(&self)?;
-        let mut file = fs::File::create("database.json")?;
-        file.write_all(data.as_bytes())?;
-        Ok(())
+    fn sqids(&self) -> &Sqids {
+        &self.sqids
     }
 
-    fn load_from_file() -> std::io::Result<Self> {
-        let file_content = fs::read_to_string("database.json")?;
-        let db: Database = serde_json::from_str(&file_content)?;
-        Ok(db)
+    fn users_counter(&self) -> &AtomicU64 {
+        &self.users_counter
     }
 }
 
-struct AppState {
-    db: Mutex<Database>,
-}
-
-
 // CREATE
-async fn create_task(app_state: web::Data<AppState>, task: web::Json<Task>) -> impl Responder {
-    let mut db = app_state
-        .db
-        .lock()
-        .expect("Failed to lock database in create task fn");
-    db.insert(task.into_inner());
-    let _ = db.save_to_file();
-    HttpResponse::Ok().finish()
+async fn create_task(
+    app_state: web::Data<AppState>,
+    task: web::Json<Task>,
+    _user: AuthUser,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = app_state.pool.get()?;
+    let mut new_task = task.into_inner();
+    new_task.id = next_id(&app_state.sqids, &app_state.tasks_counter)?;
+    insert_task(&mut conn, &new_task)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "id": new_task.id })))
 }
 
 
 // READ
-async fn read_task(app_state: web::Data<AppState>, id: web::Path<u64>) -> impl Responder {
-    let db = app_state
-        .db
-        .lock()
-        .expect("Failed to lock database in reading task");
-    match db.get(&id.into_inner()) {
-        Some(task) => HttpResponse::Ok().json(task),
-        None => HttpResponse::NotFound().finish()
+async fn read_task(
+    app_state: web::Data<AppState>,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = app_state.pool.get()?;
+    match get_task(&mut conn, &id.into_inner())? {
+        Some(task) => Ok(HttpResponse::Ok().json(task)),
+        None => Err(ApiError::NotFound),
     }
 }
 
-async fn read_all_tasks(app_state: web::Data<AppState>) -> impl Responder {
-    let db = app_state
-        .db
-        .lock()
-        .expect("Failed to lock database in reading tasks");
-    let tasks = db.get_all();
-    HttpResponse::Ok().json(tasks)
+async fn read_all_tasks(
+    app_state: web::Data<AppState>,
+    params: web::Query<ListParams>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = app_state.pool.get()?;
+    let mut tasks = get_all_tasks(&mut conn)?;
+
+    if let Some(completed) = params.completed {
+        tasks.retain(|task| task.completed == completed);
+    }
+
+    if params.sort.as_deref() == Some("name") {
+        tasks.sort_by(|a, b| a.name.cmp(&b.name));
+        if params.order.as_deref() == Some("desc") {
+            tasks.reverse();
+        }
+    }
+
+    let total = tasks.len();
+    let offset = params.offset.unwrap_or(0);
+    let limit = params.limit.unwrap_or(total);
+    let items: Vec<_> = tasks.into_iter().skip(offset).take(limit).collect();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "items": items,
+        "total": total,
+        "limit": limit,
+        "offset": offset,
+    })))
 }
 
 // UPDATE
-async fn update_task(app_state: web::Data<AppState>, task: web::Json<Task>) -> impl Responder {
-    let mut db = app_state
-        .db
-        .lock()
-        .expect("Failed to lock database in updating a task");
-    db.update(task.into_inner());
-    let _ = db.save_to_file();
-    HttpResponse::Ok().finish()
+async fn update_task(
+    app_state: web::Data<AppState>,
+    task: web::Json<Task>,
+    _user: AuthUser,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = app_state.pool.get()?;
+    update_task_row(&mut conn, &task.into_inner())?;
+    Ok(HttpResponse::Ok().finish())
 }
 
 // DELETE
-async fn delete_task(app_state: web::Data<AppState>, id: web::Path<u64>) -> impl Responder {
-    let mut db = app_state
-        .db
-        .lock()
-        .expect("Failed to lock database in deleting a task");
-    db.delete(&id.into_inner());
-    let _ = db.save_to_file();
-    HttpResponse::Ok().finish()
+async fn delete_task(
+    app_state: web::Data<AppState>,
+    id: web::Path<String>,
+    _user: AuthUser,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = app_state.pool.get()?;
+    delete_task_by_id(&mut conn, &id.into_inner())?;
+    Ok(HttpResponse::Ok().finish())
 }
 
 async fn home_page() -> actix_web::Result<HttpResponse>{
     Ok(HttpResponse::Ok().body("Hello World!"))
 }
 
-async fn register(app_state: web::Data<AppState>, user: web::Json<User>) -> impl Responder {
-    let mut db = app_state
-        .db
-        .lock()
-        .expect("Failed to lock database when registering an user");
-    db.insert_user(user.into_inner());
-    let _ = db.save_to_file();
-    HttpResponse::Ok().finish()
-}
-
-async fn login(app_state: web::Data<AppState>, user: web::Json<User>) -> impl Responder {
-    let db = app_state
-        .db
-        .lock()
-        .expect("Failed to lock database when logging in");
-    match db.get_user_by_name(&user.username) {
-        Some(stored_user) => {
-            if stored_user.password == user.password {
-                HttpResponse::Ok().body("Login successful!")
-            } else {
-                HttpResponse::BadRequest().body("Invalid username or password")
-            }
-        }
-        None => HttpResponse::Unauthorized().finish(),
-    }
-}
-
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let db: Database = match Database::load_from_file() {
-        Ok(db) => db,
-        Err(_) => Database::new(),
-    };
-
-    let data = web::Data::new(AppState { db: Mutex::new(db) });
+    let pool = common::bootstrap_pool();
+    let mut conn = pool.get().expect("Failed to get a connection");
+    let sqids = Sqids::default();
+
+    let task_ids: Vec<String> = schema::tasks::table
+        .select(schema::tasks::id)
+        .load(&mut conn)
+        .expect("Failed to load existing task ids");
+    let user_ids: Vec<String> = schema::users::table
+        .select(schema::users::id)
+        .load(&mut conn)
+        .expect("Failed to load existing user ids");
+
+    let jwt_secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+
+    let data = web::Data::new(AppState {
+        pool,
+        jwt_secret,
+        tasks_counter: AtomicU64::new(next_counter_seed(&sqids, &task_ids)),
+        users_counter: AtomicU64::new(next_counter_seed(&sqids, &user_ids)),
+        sqids,
+    });
 
     println!("Server running at port 8080");
 
@@ -201,19 +219,32 @@ async fn main() -> std::io::Result<()> {
                             .starts_with("http://localhost".as_bytes()) || origin == "null"
                     })
                     .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
-                    .allowed_headers(vec![header::AUTHORIZATION, header::ACCEPT])
+                    .allowed_headers(vec![
+                        header::AUTHORIZATION,
+                        header::ACCEPT,
+                        header::HeaderName::from_static("x-csrf-token"),
+                    ])
                     .allowed_header(header::CONTENT_TYPE)
+                    .supports_credentials()
                     .max_age(3600),
             )
             .app_data(data.clone())
             .route("/", web::get().to(home_page))
-            .route("/task", web::post().to(create_task))
-            .route("/task", web::get().to(read_all_tasks))
-            .route("/task/{id}", web::get().to(read_task))
-            .route("/task", web::put().to(update_task))
-            .route("/task/{id}", web::delete().to(delete_task))
-            .route("register", web::post().to(register))
-            .route("login", web::post().to(login))
+            .route("register", web::post().to(common::register::<AppState>))
+            .route("login", web::post().to(common::login::<AppState>))
+            .service(
+                web::scope("")
+                    .wrap(csrf::CsrfProtection::new(
+                        "csrf_token",
+                        "X-Csrf-Token",
+                        &[Method::POST, Method::PUT, Method::DELETE],
+                    ))
+                    .route("/task", web::post().to(create_task))
+                    .route("/task", web::get().to(read_all_tasks))
+                    .route("/task/{id}", web::get().to(read_task))
+                    .route("/task", web::put().to(update_task))
+                    .route("/task/{id}", web::delete().to(delete_task)),
+            )
     })
     .bind("127.0.0.1:8080")?
     .run()
@@ -0,0 +1,294 @@
+// Shared pieces that are identical across the `main` ("service") and
+// `code_template` ("task") binaries: error handling, id generation, the
+// bearer-token extractor, database bootstrap, and user registration/login.
+// The two binaries each declare `mod common;` and provide their own
+// `AppState`, implementing `JwtSecretSource` (and `UserStore`, for auth) so
+// this module's handlers can run without knowing which binary they're in.
+
+use super::schema;
+use actix_web::dev::Payload;
+use actix_web::http::header;
+use actix_web::http::StatusCode;
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use jsonwebtoken::{encode, decode, DecodingKey, EncodingKey, Header as JwtHeader, Validation};
+use serde::{Deserialize, Serialize};
+use sqids::Sqids;
+use std::fmt;
+use std::future::{ready, Ready};
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+const TOKEN_TTL_HOURS: i64 = 24;
+const MIN_PASSWORD_LEN: usize = 8;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+pub type DbPool = r2d2::Pool<ConnectionManager<SqliteConnection>>;
+
+#[derive(Debug)]
+pub enum ApiError {
+    NotFound,
+    InvalidCredentials,
+    MissingToken,
+    InvalidToken,
+    Conflict,
+    InvalidInput(String),
+    Internal(String),
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::NotFound => write!(f, "Resource not found"),
+            ApiError::InvalidCredentials => write!(f, "Invalid username and/or password"),
+            ApiError::MissingToken => write!(f, "Missing bearer token"),
+            ApiError::InvalidToken => write!(f, "Invalid or expired token"),
+            ApiError::Conflict => write!(f, "Resource already exists"),
+            ApiError::InvalidInput(message) => write!(f, "{}", message),
+            ApiError::Internal(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            ApiError::MissingToken | ApiError::InvalidToken => StatusCode::UNAUTHORIZED,
+            ApiError::Conflict => StatusCode::CONFLICT,
+            ApiError::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "status": self.status_code().canonical_reason().unwrap_or("error"),
+            "message": self.to_string(),
+        }))
+    }
+}
+
+impl From<diesel::result::Error> for ApiError {
+    fn from(err: diesel::result::Error) -> Self {
+        match err {
+            diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                _,
+            ) => ApiError::Conflict,
+            other => ApiError::Internal(other.to_string()),
+        }
+    }
+}
+
+impl From<r2d2::PoolError> for ApiError {
+    fn from(err: r2d2::PoolError) -> Self {
+        ApiError::Internal(err.to_string())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: usize,
+}
+
+// Builds the SQLite connection pool and brings the database up to the latest
+// embedded migration before the server starts accepting requests.
+pub fn bootstrap_pool() -> DbPool {
+    let database_url =
+        std::env::var("DATABASE_URL").unwrap_or_else(|_| "database.sqlite".to_string());
+    let manager = ConnectionManager::<SqliteConnection>::new(database_url);
+    let pool = r2d2::Pool::builder()
+        .build(manager)
+        .expect("Failed to build SQLite connection pool");
+
+    let mut migration_conn = pool
+        .get()
+        .expect("Failed to get a connection to run migrations");
+    migration_conn
+        .run_pending_migrations(MIGRATIONS)
+        .expect("Failed to run database migrations");
+
+    pool
+}
+
+// Encodes the next value of a monotonic counter into a short, non-guessable
+// public id, so clients can't clobber or enumerate records via sequential ids.
+pub fn next_id(sqids: &Sqids, counter: &AtomicU64) -> Result<String, ApiError> {
+    let n = counter.fetch_add(1, Ordering::SeqCst);
+    sqids
+        .encode(&[n])
+        .map_err(|err| ApiError::Internal(err.to_string()))
+}
+
+// Seeds a counter from the highest number decoded out of any existing id,
+// rather than `COUNT(*)`. Seeding from the row count regenerates an id equal
+// to a surviving row's id as soon as any row has been deleted, since
+// `sqids.encode` is a pure function of the counter value; `replace_into`
+// would then silently overwrite that row on the next insert.
+pub fn next_counter_seed(sqids: &Sqids, existing_ids: &[String]) -> u64 {
+    existing_ids
+        .iter()
+        .filter_map(|id| sqids.decode(id).first().copied())
+        .max()
+        .map_or(0, |max| max + 1)
+}
+
+pub trait JwtSecretSource {
+    fn jwt_secret(&self) -> &str;
+}
+
+// Extracts and validates the `Authorization: Bearer <token>` header, rejecting
+// the request with 401 before the handler runs if it's missing, expired, or invalid.
+pub struct AuthUser {
+    pub username: String,
+}
+
+fn authenticate<S>(req: &HttpRequest) -> Result<AuthUser, ApiError>
+where
+    S: JwtSecretSource + 'static,
+{
+    let app_state = req
+        .app_data::<web::Data<S>>()
+        .ok_or_else(|| ApiError::Internal("Missing app state".to_string()))?;
+
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    let token = token.ok_or(ApiError::MissingToken)?;
+
+    let decoded = decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(app_state.jwt_secret().as_bytes()),
+        &Validation::default(),
+    );
+
+    decoded
+        .map(|data| AuthUser {
+            username: data.claims.sub,
+        })
+        .map_err(|_| ApiError::InvalidToken)
+}
+
+impl FromRequest for AuthUser {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, ApiError>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(authenticate::<super::AppState>(req))
+    }
+}
+
+#[derive(Serialize, Deserialize, Queryable, Insertable)]
+#[diesel(table_name = schema::users)]
+pub struct User {
+    pub id: String,
+    pub username: String,
+    pub password: String,
+}
+
+fn insert_user(conn: &mut SqliteConnection, user: &User) -> Result<(), ApiError> {
+    diesel::insert_into(schema::users::table)
+        .values(user)
+        .execute(conn)?;
+    Ok(())
+}
+
+fn get_user_by_name(conn: &mut SqliteConnection, name: &str) -> Result<Option<User>, ApiError> {
+    let result = schema::users::table
+        .filter(schema::users::username.eq(name))
+        .first::<User>(conn)
+        .optional()?;
+    Ok(result)
+}
+
+// State a binary's `AppState` must expose for the shared `register`/`login`
+// handlers: the db pool, the id generator, and where to seed fresh user ids from.
+pub trait UserStore: JwtSecretSource {
+    fn pool(&self) -> &DbPool;
+    fn sqids(&self) -> &Sqids;
+    fn users_counter(&self) -> &AtomicU64;
+}
+
+pub async fn register<S>(
+    app_state: web::Data<S>,
+    user: web::Json<User>,
+) -> Result<HttpResponse, ApiError>
+where
+    S: UserStore + 'static,
+{
+    if user.password.len() < MIN_PASSWORD_LEN {
+        return Err(ApiError::InvalidInput(format!(
+            "Password must be at least {} characters long",
+            MIN_PASSWORD_LEN
+        )));
+    }
+
+    let mut conn = app_state.pool().get()?;
+
+    if get_user_by_name(&mut conn, &user.username)?.is_some() {
+        return Err(ApiError::Conflict);
+    }
+
+    let salt = SaltString::generate(&mut OsRng);
+    let hashed_password = Argon2::default()
+        .hash_password(user.password.as_bytes(), &salt)
+        .map_err(|err| ApiError::Internal(err.to_string()))?
+        .to_string();
+    let new_user = User {
+        id: next_id(app_state.sqids(), app_state.users_counter())?,
+        username: user.username.clone(),
+        password: hashed_password,
+    };
+
+    insert_user(&mut conn, &new_user)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "id": new_user.id })))
+}
+
+pub async fn login<S>(
+    app_state: web::Data<S>,
+    user: web::Json<User>,
+) -> Result<HttpResponse, ApiError>
+where
+    S: UserStore + 'static,
+{
+    let mut conn = app_state.pool().get()?;
+    match get_user_by_name(&mut conn, &user.username)? {
+        Some(stored_user) => {
+            let parsed_hash = PasswordHash::new(&stored_user.password)
+                .map_err(|err| ApiError::Internal(err.to_string()))?;
+            let verified = Argon2::default()
+                .verify_password(user.password.as_bytes(), &parsed_hash)
+                .is_ok();
+            if verified {
+                let exp = (Utc::now() + chrono::Duration::hours(TOKEN_TTL_HOURS)).timestamp() as usize;
+                let claims = Claims {
+                    sub: user.username.clone(),
+                    exp,
+                };
+                let token = encode(
+                    &JwtHeader::default(),
+                    &claims,
+                    &EncodingKey::from_secret(app_state.jwt_secret().as_bytes()),
+                )
+                .map_err(|err| ApiError::Internal(err.to_string()))?;
+                Ok(HttpResponse::Ok().json(serde_json::json!({ "token": token })))
+            } else {
+                Err(ApiError::InvalidCredentials)
+            }
+        }
+        None => Err(ApiError::InvalidCredentials),
+    }
+}
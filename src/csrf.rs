@@ -0,0 +1,129 @@
+use actix_web::body::EitherBody;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::Method;
+use actix_web::{Error, HttpResponse};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use std::collections::HashSet;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use subtle::ConstantTimeEq;
+
+// Double-submit CSRF protection: a random token is set as a cookie on safe
+// requests, and state-changing requests must echo it back in a header. The
+// cookie alone isn't enough to forge a request since cross-site requests
+// can't read it, only resend it.
+#[derive(Clone)]
+pub struct CsrfProtection {
+    cookie_name: &'static str,
+    header_name: &'static str,
+    protected_methods: Rc<HashSet<Method>>,
+}
+
+impl CsrfProtection {
+    pub fn new(
+        cookie_name: &'static str,
+        header_name: &'static str,
+        protected_methods: &[Method],
+    ) -> Self {
+        Self {
+            cookie_name,
+            header_name,
+            protected_methods: Rc::new(protected_methods.iter().cloned().collect()),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for CsrfProtection
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = CsrfProtectionMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CsrfProtectionMiddleware {
+            service,
+            cookie_name: self.cookie_name,
+            header_name: self.header_name,
+            protected_methods: self.protected_methods.clone(),
+        }))
+    }
+}
+
+pub struct CsrfProtectionMiddleware<S> {
+    service: S,
+    cookie_name: &'static str,
+    header_name: &'static str,
+    protected_methods: Rc<HashSet<Method>>,
+}
+
+impl<S, B> Service<ServiceRequest> for CsrfProtectionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if self.protected_methods.contains(req.method()) {
+            let cookie_token = req.cookie(self.cookie_name).map(|c| c.value().to_string());
+            let header_token = req
+                .headers()
+                .get(self.header_name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string());
+
+            let valid = matches!(
+                (&cookie_token, &header_token),
+                (Some(cookie_value), Some(header_value))
+                    if bool::from(cookie_value.as_bytes().ct_eq(header_value.as_bytes()))
+            );
+
+            if !valid {
+                let (req, _) = req.into_parts();
+                let response = HttpResponse::Forbidden()
+                    .json(serde_json::json!({
+                        "status": "Forbidden",
+                        "message": "Missing or invalid CSRF token",
+                    }))
+                    .map_into_right_body();
+                return Box::pin(async move { Ok(ServiceResponse::new(req, response)) });
+            }
+        }
+
+        let cookie_name = self.cookie_name;
+        let issue_cookie = req.cookie(cookie_name).is_none();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await?;
+            let mut res = res.map_into_left_body();
+            if issue_cookie {
+                let cookie = Cookie::build(cookie_name, generate_csrf_token())
+                    .same_site(SameSite::Strict)
+                    .path("/")
+                    .finish();
+                let _ = res.response_mut().add_cookie(&cookie);
+            }
+            Ok(res)
+        })
+    }
+}
+
+fn generate_csrf_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    STANDARD.encode(bytes)
+}
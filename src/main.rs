@@ -1,163 +1,238 @@
+mod common;
+mod csrf;
+mod schema;
+
 use actix_cors::Cors;
-use actix_web::{http::header, web, App, HttpResponse, HttpServer, Responder};
+use actix_files::Files;
+use actix_multipart::Multipart;
+use actix_web::{http::header, http::Method, web, App, HttpResponse, HttpServer};
 use serde::{Deserialize, Serialize};
-use bcrypt::{hash, verify, DEFAULT_COST};
-use std::collections::HashMap;
-use std::fs;
-use std::io::Write;
-use std::sync::Mutex;
-use chrono::prelude::*;
-
-#[derive(Serialize, Debug, Deserialize, Clone)]
+use common::{next_counter_seed, next_id, ApiError, AuthUser, DbPool, JwtSecretSource, UserStore};
+use diesel::prelude::*;
+use futures_util::StreamExt as _;
+use sqids::Sqids;
+use std::path::Path;
+use std::sync::atomic::AtomicU64;
+
+const UPLOAD_DIR: &str = "uploads";
+const THUMBNAIL_MAX_DIM: u32 = 256;
+const MAX_IMAGE_BYTES: usize = 5 * 1024 * 1024;
+
+#[derive(Serialize, Debug, Deserialize, Clone, Queryable, Insertable, AsChangeset)]
+#[diesel(table_name = schema::services)]
 struct Service {
-    id: u64,
+    id: String,
     name: String,
     price: f32,
-    duration: u32,
+    duration: i32,
+    #[serde(default)]
+    image_path: Option<String>,
+    #[serde(default)]
+    thumbnail_path: Option<String>,
 }
 
-#[derive(Serialize, Deserialize)]
-struct User {
-    id: u64,
-    username: String,
-    password: String,
+#[derive(Deserialize)]
+struct ListParams {
+    limit: Option<usize>,
+    offset: Option<usize>,
+    sort: Option<String>,
+    order: Option<String>,
+    max_price: Option<f32>,
+    min_duration: Option<i32>,
 }
 
-#[derive(Serialize, Deserialize)]
-struct Claims {
-    sub: String,
-    exp: usize,
+fn insert_service(conn: &mut SqliteConnection, service: &Service) -> Result<(), ApiError> {
+    diesel::replace_into(schema::services::table)
+        .values(service)
+        .execute(conn)?;
+    Ok(())
 }
 
-#[derive(Serialize, Deserialize)]
-struct Database {
-    services: HashMap<u64, Service>,
-    users: HashMap<u64, User>,
+fn get_service(conn: &mut SqliteConnection, service_id: &str) -> Result<Option<Service>, ApiError> {
+    let result = schema::services::table
+        .find(service_id)
+        .first::<Service>(conn)
+        .optional()?;
+    Ok(result)
 }
 
-impl Database {
-    fn new() -> Self {
-        Self {
-            services: HashMap::new(),
-            users: HashMap::new(),
-        }
-    }
+fn get_all_services(conn: &mut SqliteConnection) -> Result<Vec<Service>, ApiError> {
+    Ok(schema::services::table.load::<Service>(conn)?)
+}
 
-    fn insert(&mut self, service: Service) {
-        self.services.insert(service.id, service);
-    }
+struct AppState {
+    pool: DbPool,
+    jwt_secret: String,
+    sqids: Sqids,
+    services_counter: AtomicU64,
+    users_counter: AtomicU64,
+}
 
-    fn get(&self, id: &u64) -> Option<&Service> {
-        self.services.get(id)
+impl JwtSecretSource for AppState {
+    fn jwt_secret(&self) -> &str {
+        &self.jwt_secret
     }
+}
 
-    fn get_all(&self) -> Vec<&Service> {
-        self.services.values().collect()
+impl UserStore for AppState {
+    fn pool(&self) -> &DbPool {
+        &self.pool
     }
 
-    fn insert_user(&mut self, user: User) {
-        self.users.insert(user.id, user);
+    fn sqids(&self) -> &Sqids {
+        &self.sqids
     }
 
-    fn get_user_by_name(&self, username: &str) -> Option<&User> {
-        self.users.values().find(|u| u.username == username)
+    fn users_counter(&self) -> &AtomicU64 {
+        &self.users_counter
     }
+}
 
-    fn save_to_file(&self) -> std::io::Result<()> {
-        let data = serde_json::to_string(&self)?;
-        let mut file = fs::File::create("database.json")?;
-        file.write_all(data.as_bytes())?;
-        Ok(())
-    }
+async fn create_service(
+    app_state: web::Data<AppState>,
+    service: web::Json<Service>,
+    _user: AuthUser,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = app_state.pool.get()?;
+    let mut new_service = service.into_inner();
+    new_service.id = next_id(&app_state.sqids, &app_state.services_counter)?;
+    insert_service(&mut conn, &new_service)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "id": new_service.id })))
+}
 
-    fn load_from_file() -> std::io::Result<Self> {
-        let file_content = fs::read_to_string("database.json")?;
-        let db: Database = serde_json::from_str(&file_content)?;
-        Ok(db)
+async fn read_service(
+    app_state: web::Data<AppState>,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = app_state.pool.get()?;
+    match get_service(&mut conn, &id.into_inner())? {
+        Some(service) => Ok(HttpResponse::Ok().json(service)),
+        None => Err(ApiError::NotFound),
     }
 }
 
-struct AppState {
-    db: Mutex<Database>,
-}
+async fn read_all_services(
+    app_state: web::Data<AppState>,
+    params: web::Query<ListParams>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = app_state.pool.get()?;
+    let mut services = get_all_services(&mut conn)?;
 
-async fn create_service(app_state: web::Data<AppState>, service: web::Json<Service>) -> impl Responder {
-    let mut db = app_state
-        .db
-        .lock()
-        .expect("Failed to lock database in create service fn");
-    db.insert(service.into_inner());
-    let _ = db.save_to_file();
-    HttpResponse::Ok().finish()
-}
+    if let Some(max_price) = params.max_price {
+        services.retain(|service| service.price <= max_price);
+    }
+    if let Some(min_duration) = params.min_duration {
+        services.retain(|service| service.duration >= min_duration);
+    }
 
-async fn read_service(app_state: web::Data<AppState>, id: web::Path<u64>) -> impl Responder {
-    let db = app_state
-        .db
-        .lock()
-        .expect("Failed to lock database in reading service");
-    match db.get(&id.into_inner()) {
-        Some(service) => HttpResponse::Ok().json(service),
-        None => HttpResponse::NotFound().finish()
+    if let Some(sort) = params.sort.as_deref() {
+        match sort {
+            "price" => services.sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap()),
+            "duration" => services.sort_by_key(|service| service.duration),
+            "name" => services.sort_by(|a, b| a.name.cmp(&b.name)),
+            _ => {}
+        }
+        if params.order.as_deref() == Some("desc") {
+            services.reverse();
+        }
     }
-}
 
-async fn read_all_services(app_state: web::Data<AppState>) -> impl Responder {
-    let db = app_state
-        .db
-        .lock()
-        .expect("Failed to lock database in reading services");
-    let services = db.get_all();
-    HttpResponse::Ok().json(services)
-}
+    let total = services.len();
+    let offset = params.offset.unwrap_or(0);
+    let limit = params.limit.unwrap_or(total);
+    let items: Vec<_> = services.into_iter().skip(offset).take(limit).collect();
 
-async fn home_page() -> actix_web::Result<HttpResponse>{
-    Ok(HttpResponse::Ok().body("Hello World!"))
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "items": items,
+        "total": total,
+        "limit": limit,
+        "offset": offset,
+    })))
 }
 
-async fn register(app_state: web::Data<AppState>, user: web::Json<User>) -> impl Responder {
-    let mut db = app_state
-        .db
-        .lock()
-        .expect("Failed to lock database when registering an user");
-
-    let hashed_password = hash(&user.password, DEFAULT_COST).expect("Failed to hash password");
-    let new_user = User {
-        id: user.id,
-        username: user.username.clone(),
-        password: hashed_password,
-    };
-
-    db.insert_user(new_user);
-    let _ = db.save_to_file();
-    HttpResponse::Ok().finish()
-}
+async fn upload_service_image(
+    app_state: web::Data<AppState>,
+    id: web::Path<String>,
+    mut payload: Multipart,
+    _user: AuthUser,
+) -> Result<HttpResponse, ApiError> {
+    let service_id = id.into_inner();
+    let mut conn = app_state.pool.get()?;
+    let mut service = get_service(&mut conn, &service_id)?.ok_or(ApiError::NotFound)?;
 
-async fn login(app_state: web::Data<AppState>, user: web::Json<User>) -> impl Responder {
-    let db = app_state
-        .db
-        .lock()
-        .expect("Failed to lock database when logging in");
-    match db.get_user_by_name(&user.username) {
-        Some(stored_user) => {
-            if verify(&user.password, &stored_user.password).expect("Failed to verify password") {
-                HttpResponse::Ok().body("Login successful")
-            } else {
-                HttpResponse::BadRequest().body("Invalid username and/or password")
+    let mut image_bytes: Option<Vec<u8>> = None;
+    while let Some(field) = payload.next().await {
+        let mut field = field.map_err(|err| ApiError::InvalidInput(err.to_string()))?;
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(|err| ApiError::InvalidInput(err.to_string()))?;
+            if bytes.len() + chunk.len() > MAX_IMAGE_BYTES {
+                return Err(ApiError::InvalidInput(format!(
+                    "Image exceeds the {} byte limit",
+                    MAX_IMAGE_BYTES
+                )));
             }
+            bytes.extend_from_slice(&chunk);
         }
-        None => HttpResponse::Unauthorized().body("Invalid username or password"),
+        image_bytes = Some(bytes);
     }
+    let bytes =
+        image_bytes.ok_or_else(|| ApiError::InvalidInput("Missing image field".to_string()))?;
+
+    let format = image::guess_format(&bytes)
+        .map_err(|_| ApiError::InvalidInput("Unsupported image format".to_string()))?;
+    let img = image::load_from_memory_with_format(&bytes, format)
+        .map_err(|err| ApiError::InvalidInput(err.to_string()))?;
+    let extension = format.extensions_str()[0];
+
+    let service_dir = Path::new(UPLOAD_DIR).join(&service_id);
+    std::fs::create_dir_all(&service_dir).map_err(|err| ApiError::Internal(err.to_string()))?;
+
+    let original_path = service_dir.join(format!("original.{extension}"));
+    img.save(&original_path)
+        .map_err(|err| ApiError::Internal(err.to_string()))?;
+
+    let thumbnail = image::imageops::thumbnail(&img, THUMBNAIL_MAX_DIM, THUMBNAIL_MAX_DIM);
+    let thumbnail_path = service_dir.join(format!("thumbnail.{extension}"));
+    thumbnail
+        .save(&thumbnail_path)
+        .map_err(|err| ApiError::Internal(err.to_string()))?;
+
+    service.image_path = Some(original_path.to_string_lossy().to_string());
+    service.thumbnail_path = Some(thumbnail_path.to_string_lossy().to_string());
+    insert_service(&mut conn, &service)?;
+
+    Ok(HttpResponse::Ok().json(service))
+}
+
+async fn home_page() -> actix_web::Result<HttpResponse>{
+    Ok(HttpResponse::Ok().body("Hello World!"))
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let db: Database = match Database::load_from_file() {
-        Ok(db) => db,
-        Err(_) => Database::new(),
-    };
+    let pool = common::bootstrap_pool();
+    let mut conn = pool.get().expect("Failed to get a connection");
+    let sqids = Sqids::default();
+
+    let service_ids: Vec<String> = schema::services::table
+        .select(schema::services::id)
+        .load(&mut conn)
+        .expect("Failed to load existing service ids");
+    let user_ids: Vec<String> = schema::users::table
+        .select(schema::users::id)
+        .load(&mut conn)
+        .expect("Failed to load existing user ids");
 
-    let data = web::Data::new(AppState { db: Mutex::new(db) });
+    let jwt_secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+
+    let data = web::Data::new(AppState {
+        pool,
+        jwt_secret,
+        services_counter: AtomicU64::new(next_counter_seed(&sqids, &service_ids)),
+        users_counter: AtomicU64::new(next_counter_seed(&sqids, &user_ids)),
+        sqids,
+    });
 
     println!("Server running at port 8080");
 
@@ -171,17 +246,32 @@ async fn main() -> std::io::Result<()> {
                             .starts_with("http://localhost".as_bytes()) || origin == "null"
                     })
                     .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
-                    .allowed_headers(vec![header::AUTHORIZATION, header::ACCEPT])
+                    .allowed_headers(vec![
+                        header::AUTHORIZATION,
+                        header::ACCEPT,
+                        header::HeaderName::from_static("x-csrf-token"),
+                    ])
                     .allowed_header(header::CONTENT_TYPE)
+                    .supports_credentials()
                     .max_age(3600),
             )
             .app_data(data.clone())
             .route("/", web::get().to(home_page))
-            .route("/service", web::post().to(create_service))
-            .route("/service", web::get().to(read_all_services))
-            .route("/service/{id}", web::get().to(read_service))
-            .route("register", web::post().to(register))
-            .route("login", web::post().to(login))
+            .route("register", web::post().to(common::register::<AppState>))
+            .route("login", web::post().to(common::login::<AppState>))
+            .service(
+                web::scope("")
+                    .wrap(csrf::CsrfProtection::new(
+                        "csrf_token",
+                        "X-Csrf-Token",
+                        &[Method::POST, Method::PUT, Method::DELETE],
+                    ))
+                    .route("/service", web::post().to(create_service))
+                    .route("/service", web::get().to(read_all_services))
+                    .route("/service/{id}", web::get().to(read_service))
+                    .route("/service/{id}/image", web::post().to(upload_service_image))
+                    .service(Files::new("/uploads", UPLOAD_DIR)),
+            )
     })
     .bind("127.0.0.1:8080")?
     .run()
@@ -0,0 +1,26 @@
+diesel::table! {
+    tasks (id) {
+        id -> Text,
+        name -> Text,
+        completed -> Bool,
+    }
+}
+
+diesel::table! {
+    services (id) {
+        id -> Text,
+        name -> Text,
+        price -> Float,
+        duration -> Integer,
+        image_path -> Nullable<Text>,
+        thumbnail_path -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    users (id) {
+        id -> Text,
+        username -> Text,
+        password -> Text,
+    }
+}